@@ -1,14 +1,100 @@
 use std::fs;
-use std::process::{Command, exit};
-use std::borrow::Borrow;
+use std::fmt;
+use std::path::Path;
+use std::process::Command;
 use std::error::Error as StdError;
 
 use colored::Colorize;
-use chrono::{Local, Datelike};
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use sha2::{Digest, Sha256};
+use x509_parser::certificate::X509Certificate;
+use x509_parser::pkcs7::pkcs7_from_der;
 
 use {Error, Config, Criticity, Result, print_error, print_vulnerability, print_warning};
 use results::{Results, Vulnerability};
 
+/// The structured fields we care about from a signing certificate, however
+/// it was obtained: either straight from the DER via the native parser, or
+/// scraped from `openssl`'s text output as a fallback.
+#[derive(Debug, Clone)]
+struct Certificate {
+    issuer: String,
+    subject: String,
+    not_before: DateTime<Utc>,
+    not_after: DateTime<Utc>,
+    serial: String,
+    signature_algorithm: String,
+    public_key_algorithm: String,
+    key_bits: u32,
+    is_self_signed: bool,
+    /// How this certificate links (or fails to link) to the next one in
+    /// its chain.
+    chain_link: ChainLink,
+    /// Lower-case hex SHA-256 of the certificate's DER encoding, used to
+    /// match it against a database of known-compromised keys. `None` when
+    /// the raw bytes weren't available (the `openssl` fallback path).
+    fingerprint: Option<String>,
+    /// Lower-case hex SHA-256 of the public key bits inside the
+    /// certificate's `SubjectPublicKeyInfo` (the algorithm identifier is not
+    /// part of the hash). A leaked key is usually re-wrapped in a new, otherwise-unrelated
+    /// certificate, so matching on the key alone catches those cases too.
+    public_key_fingerprint: Option<String>,
+}
+
+/// How a certificate relates to the next certificate in its chain. Kept
+/// distinct from a plain `bool` so that "no issuer found" can't be confused
+/// with "this is a legitimate root" — both used to collapse to `None`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ChainLink {
+    /// The certificate is self-signed and terminates the chain.
+    Root,
+    /// The next certificate in the chain verifies as this one's issuer.
+    Verified,
+    /// This certificate's signature does not verify against the next
+    /// certificate in the chain.
+    Failed,
+    /// This isn't a root, but no certificate that could be its issuer was
+    /// found in the bundle (e.g. a chain missing its root certificate, or
+    /// an orphaned certificate).
+    IssuerNotFound,
+    /// The `openssl` text-scraping fallback can't check this.
+    Unknown,
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.input(data);
+    let digest = hasher.result();
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Normalizes a distinguished name for comparison: lower-cases it and trims
+/// the whitespace around each `RDN=value` pair, so that e.g. `CN=Foo, O=Bar`
+/// and `cn=foo,o=bar` are recognized as the same DN.
+fn normalize_dn(dn: &str) -> String {
+    dn.split(',')
+        .map(|rdn| rdn.trim().to_lowercase())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+impl fmt::Display for Certificate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Serial Number: {}", self.serial)?;
+        writeln!(f, "Signature Algorithm: {}", self.signature_algorithm)?;
+        writeln!(f, "Issuer: {}", self.issuer)?;
+        writeln!(f,
+                 "Validity: {} to {}",
+                 self.not_before,
+                 self.not_after)?;
+        writeln!(f, "Subject: {}", self.subject)?;
+        writeln!(f,
+                 "Public Key Algorithm: {} ({} bits)",
+                 self.public_key_algorithm,
+                 self.key_bits)
+    }
+}
+
 fn parse_month<S: AsRef<str>>(month_str: S) -> u32 {
     match month_str.as_ref() {
         "Jan" => 1,
@@ -27,6 +113,631 @@ fn parse_month<S: AsRef<str>>(month_str: S) -> u32 {
     }
 }
 
+/// Parses every certificate in a DER-encoded PKCS#7 signature block using a
+/// native Rust parser. This is the preferred path: unlike the `openssl`
+/// fallback it doesn't depend on an external binary being on `PATH` and it
+/// gives us structured fields instead of human-readable, locale-dependent
+/// text we'd have to scrape.
+fn parse_certificates_native(data: &[u8]) -> ::std::result::Result<Vec<Certificate>, Box<StdError>> {
+    let (_, pkcs7) = pkcs7_from_der(data).map_err(|e| format!("invalid PKCS#7 data: {:?}", e))?;
+    let certificates = pkcs7.signed_data
+        .certificates
+        .ok_or_else(|| "the PKCS#7 block does not contain any certificates".to_string())?;
+
+    let nodes: Vec<ChainNode> = certificates.iter()
+        .map(|cert| {
+            ChainNode {
+                subject: cert.subject().to_string(),
+                issuer: cert.issuer().to_string(),
+            }
+        })
+        .collect();
+
+    Ok(order_chain(&nodes)
+        .into_iter()
+        .map(|(index, issuer_index)| {
+            certificate_from_x509(&certificates[index], issuer_index.map(|i| &certificates[i]))
+        })
+        .collect())
+}
+
+/// A certificate's identity, as needed to link it into a chain: just its
+/// subject and issuer distinguished names.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ChainNode {
+    subject: String,
+    issuer: String,
+}
+
+/// Orders an (unordered) set of certificates into a chain, leaf first, and
+/// records which certificate (if any) was actually found to be each one's
+/// issuer.
+///
+/// The leaf is the certificate nobody else in the set was issued by. Each
+/// following link is found by matching a certificate's issuer DN against
+/// the subject DN of the next one, and that matched index is what gets
+/// returned as the "next" certificate — never a position re-derived from
+/// the output order, which would misattribute an unrelated or orphaned
+/// certificate as the issuer of whatever ends up next to it in the array.
+/// A certificate that can't be linked this way (a missing root, or a stray
+/// certificate bundled in the same block) is appended at the end with
+/// `None` as its issuer, so it's still reported rather than silently
+/// dropped.
+///
+/// Returns pairs of `(original index into nodes, original index of the
+/// certificate that issued it)`.
+fn order_chain(nodes: &[ChainNode]) -> Vec<(usize, Option<usize>)> {
+    let mut remaining: Vec<usize> = (0..nodes.len()).collect();
+
+    if remaining.len() <= 1 {
+        return remaining.into_iter().map(|index| (index, None)).collect();
+    }
+
+    let leaf_pos = remaining.iter()
+        .position(|&i| {
+            !remaining.iter()
+                .any(|&j| normalize_dn(&nodes[j].issuer) == normalize_dn(&nodes[i].subject))
+        })
+        .unwrap_or(0);
+
+    let mut chain = vec![remaining.remove(leaf_pos)];
+    let mut issuers = Vec::new();
+
+    loop {
+        let current = *chain.last().unwrap();
+        if normalize_dn(&nodes[current].issuer) == normalize_dn(&nodes[current].subject) {
+            issuers.push(None);
+            break;
+        }
+
+        let next_pos = remaining.iter()
+            .position(|&i| normalize_dn(&nodes[i].subject) == normalize_dn(&nodes[current].issuer));
+        match next_pos {
+            Some(pos) => {
+                let next = remaining.remove(pos);
+                issuers.push(Some(next));
+                chain.push(next);
+            }
+            None => {
+                issuers.push(None);
+                break;
+            }
+        }
+    }
+
+    // Whatever's left couldn't be linked into the chain at all (a stray or
+    // unrelated certificate) — report it too, but without inventing an
+    // issuer for it.
+    for index in remaining {
+        chain.push(index);
+        issuers.push(None);
+    }
+
+    chain.into_iter().zip(issuers).collect()
+}
+
+fn certificate_from_x509(cert: &X509Certificate, issuer: Option<&X509Certificate>) -> Certificate {
+    let validity = cert.validity();
+    let public_key = cert.public_key();
+    let issuer_dn = cert.issuer().to_string();
+    let subject = cert.subject().to_string();
+
+    // A certificate is self-signed when issuer and subject are the same
+    // identity *and* its signature actually verifies against its own public
+    // key. Checking the DN alone isn't enough: a cert could claim the same
+    // issuer/subject without the signature backing that claim up.
+    let is_self_signed = normalize_dn(&issuer_dn) == normalize_dn(&subject) &&
+                          cert.verify_signature(None).is_ok();
+
+    // For every non-root certificate in the chain, check that it is
+    // actually signed by the certificate that follows it, rather than just
+    // trusting that the issuer/subject DNs line up. A non-root certificate
+    // with no matching issuer in the bundle is just as much a broken chain
+    // as one whose signature fails to verify, so it gets its own state
+    // instead of collapsing into the same "nothing to check" bucket as a
+    // legitimate root.
+    let chain_link = if is_self_signed {
+        ChainLink::Root
+    } else {
+        match issuer {
+            Some(issuer_cert) => {
+                if cert.verify_signature(Some(issuer_cert.public_key())).is_ok() {
+                    ChainLink::Verified
+                } else {
+                    ChainLink::Failed
+                }
+            }
+            None => ChainLink::IssuerNotFound,
+        }
+    };
+
+    Certificate {
+        issuer: issuer_dn,
+        subject: subject,
+        not_before: Utc.timestamp(validity.not_before.timestamp(), 0),
+        not_after: Utc.timestamp(validity.not_after.timestamp(), 0),
+        serial: cert.raw_serial_as_string(),
+        signature_algorithm: cert.signature_algorithm.algorithm.to_id_string(),
+        public_key_algorithm: public_key.algorithm.algorithm.to_id_string(),
+        key_bits: public_key.parsed().map(|k| k.key_size() as u32).unwrap_or(0),
+        is_self_signed: is_self_signed,
+        chain_link: chain_link,
+        fingerprint: Some(sha256_hex(cert.as_ref())),
+        public_key_fingerprint: Some(sha256_hex(public_key.subject_public_key.data)),
+    }
+}
+
+/// Falls back to shelling out to `openssl` and scraping its `-text` output
+/// when the native parser can't make sense of a certificate. This is the
+/// original implementation: it's fragile (the field positions depend on
+/// `openssl`'s locale and version) and it returns an error if `openssl`
+/// can't be run or fails, but it's kept as a last resort so certificate
+/// analysis still works on inputs the native parser doesn't understand yet.
+fn parse_certificate_legacy(path: &Path, config: &Config) -> Result<Vec<Certificate>> {
+    let output = Command::new("openssl")
+        .arg("pkcs7")
+        .arg("-inform")
+        .arg("DER")
+        .arg("-in")
+        .arg(path.to_str().unwrap())
+        .arg("-noout")
+        .arg("-print_certs")
+        .arg("-text")
+        .output();
+
+    if output.is_err() {
+        print_error(format!("There was an error when executing the openssl command to \
+                             check the certificate: {}",
+                            output.err().unwrap()),
+                    config.is_verbose());
+        return Err(Error::Unknown.into());
+    }
+
+    let output = output.unwrap();
+    if !output.status.success() {
+        print_error(format!("The openssl command returned an error. More info: {}",
+                            String::from_utf8_lossy(&output.stderr[..])),
+                    config.is_verbose());
+        return Err(Error::Unknown.into());
+    };
+
+    let cmd = String::from_utf8_lossy(&output.stdout);
+    if config.is_verbose() {
+        println!("{}", cmd);
+    }
+
+    let mut issuer = String::new();
+    let mut subject = String::new();
+    let mut before = String::new();
+    let mut after = String::new();
+    for line in cmd.lines() {
+        if line.contains("Issuer:") {
+            issuer = line.to_owned();
+        }
+        if line.contains("Subject:") {
+            subject = line.to_owned();
+        }
+        if line.contains("Not Before:") {
+            before = line.to_owned();
+        }
+        if line.contains("Not After :") {
+            after = line.to_owned();
+        }
+    }
+
+    let issuer = issuer.split(": ").nth(1).unwrap_or("").to_owned();
+    let subject = subject.split(": ").nth(1).unwrap_or("").to_owned();
+    let not_before = parse_legacy_date(before.split(": ").nth(1).unwrap_or(""));
+    let not_after = parse_legacy_date(after.split(": ").nth(1).unwrap_or(""));
+    // We don't have the DER bytes here to verify a signature, so the DN
+    // comparison is the best signal this fallback path can offer.
+    let is_self_signed = normalize_dn(&issuer) == normalize_dn(&subject);
+    let chain_link = if is_self_signed {
+        ChainLink::Root
+    } else {
+        ChainLink::Unknown
+    };
+
+    Ok(vec![Certificate {
+                issuer: issuer,
+                subject: subject,
+                not_before: not_before,
+                not_after: not_after,
+                serial: String::new(),
+                signature_algorithm: String::new(),
+                public_key_algorithm: String::new(),
+                key_bits: 0,
+                is_self_signed: is_self_signed,
+                chain_link: chain_link,
+                fingerprint: None,
+                public_key_fingerprint: None,
+            }])
+}
+
+/// Parses the brittle `openssl -text` date format (e.g. `Jan  1 00:00:00
+/// 2020 GMT`), reusing the original field-slicing approach.
+fn parse_legacy_date(date_str: &str) -> DateTime<Utc> {
+    if date_str.len() < 20 {
+        return Utc.timestamp(0, 0);
+    }
+
+    let cert_year = date_str[16..20].parse::<i32>().unwrap_or(1970);
+    let cert_month = parse_month(&date_str[0..3]);
+    let cert_day = match date_str[4..6].parse::<u32>() {
+        // if day < 10, only one digit is printed
+        Ok(n) => n,
+        Err(_) => date_str[5..6].parse::<u32>().unwrap_or(1),
+    };
+
+    Utc.ymd(cert_year, cert_month.max(1), cert_day.max(1)).and_hms(0, 0, 0)
+}
+
+/// A single publicly disclosed, compromised signing key: most notably the
+/// various leaked AOSP platform signing keys, but also debug/test keys that
+/// have leaked or were never meant to sign release builds.
+struct LeakedKey {
+    name: String,
+    certificate_sha256: Option<String>,
+    public_key_sha256: Option<String>,
+}
+
+/// A database of known-leaked signing keys, loaded from a local file or
+/// directory so it can be refreshed without recompiling. Every entry is a
+/// tab-separated `name\tcertificate sha256\tpublic key sha256` line, either
+/// hash may be left empty when it's not known.
+struct LeakedKeyDatabase {
+    keys: Vec<LeakedKey>,
+}
+
+/// Parses one `name\tcertificate sha256\tpublic key sha256` entry per line,
+/// skipping blank lines and `#` comments. Split out of `load` so the parsing
+/// itself can be tested without touching the filesystem.
+fn parse_leaked_keys(contents: &str) -> Vec<LeakedKey> {
+    let mut keys = Vec::new();
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut fields = line.splitn(3, '\t');
+        let name = fields.next().unwrap_or("unknown leaked key").to_owned();
+        let certificate_sha256 = fields.next()
+            .map(str::to_lowercase)
+            .filter(|fingerprint| !fingerprint.is_empty());
+        let public_key_sha256 = fields.next()
+            .map(str::to_lowercase)
+            .filter(|fingerprint| !fingerprint.is_empty());
+
+        keys.push(LeakedKey {
+            name: name,
+            certificate_sha256: certificate_sha256,
+            public_key_sha256: public_key_sha256,
+        });
+    }
+    keys
+}
+
+impl LeakedKeyDatabase {
+    fn load(path: &Path) -> Result<LeakedKeyDatabase> {
+        let files = if path.is_dir() {
+            try!(fs::read_dir(path)).filter_map(|entry| entry.ok()).map(|entry| entry.path()).collect()
+        } else {
+            vec![path.to_path_buf()]
+        };
+
+        let mut keys = Vec::new();
+        for file in files {
+            let contents = try!(fs::read_to_string(&file));
+            keys.extend(parse_leaked_keys(&contents));
+        }
+
+        Ok(LeakedKeyDatabase { keys: keys })
+    }
+
+    fn matching<'a>(&'a self, cert: &Certificate) -> Option<&'a LeakedKey> {
+        self.keys.iter().find(|key| {
+            (key.certificate_sha256.is_some() && key.certificate_sha256 == cert.fingerprint) ||
+            (key.public_key_sha256.is_some() && key.public_key_sha256 == cert.public_key_fingerprint)
+        })
+    }
+}
+
+/// Matches a certificate's fingerprints against the database of known-leaked
+/// signing keys pointed at by `Config`. Replaces the old hard-coded
+/// `issuer.contains("Android Debug")` heuristic with a data-driven check
+/// that covers any publicly disclosed key, not just the Android debug one.
+///
+/// The database is the same for every certificate analyzed in a run, so
+/// it's loaded once by the caller and passed in here rather than re-read
+/// from disk per certificate.
+fn check_known_leaked_keys(config: &Config,
+                           database: &LeakedKeyDatabase,
+                           cert: &Certificate,
+                           results: &mut Results) {
+    if let Some(leaked_key) = database.matching(cert) {
+        let criticity = Criticity::Critical;
+        let description = format!("The application is signed with \"{}\", a signing key that is \
+                                   publicly known to be leaked or compromised. Anyone with access \
+                                   to this key can sign an application that impersonates this \
+                                   one.",
+                                  leaked_key.name);
+
+        let vuln = Vulnerability::new(criticity,
+                                      "Known leaked signing key",
+                                      description.clone(),
+                                      None::<String>,
+                                      None,
+                                      None,
+                                      None::<String>);
+        results.add_vulnerability(vuln);
+
+        if config.is_verbose() {
+            print_vulnerability(&description, criticity);
+        }
+    }
+}
+
+/// Grades a key size against the configured thresholds: `High` below
+/// `critical_bits`, `Medium` below `min_bits`, and not worth flagging
+/// otherwise.
+fn classify_key_size(key_bits: u32, critical_bits: u32, min_bits: u32) -> Option<Criticity> {
+    if key_bits < critical_bits {
+        Some(Criticity::High)
+    } else if key_bits < min_bits {
+        Some(Criticity::Medium)
+    } else {
+        None
+    }
+}
+
+/// Checks a certificate's signature algorithm and key size against the
+/// configured policy, flagging algorithms and key sizes that are considered
+/// broken or too weak for current use.
+fn check_certificate_policy(config: &Config, cert: &Certificate, results: &mut Results) {
+    let algorithm = cert.signature_algorithm.to_lowercase();
+
+    for &(ref weak_algorithm, criticity) in config.get_rejected_signature_algorithms() {
+        if algorithm.contains(&weak_algorithm.to_lowercase()) {
+            let description = format!("The certificate is signed using {}, which is considered \
+                                       broken or too weak for current use. An attacker may be \
+                                       able to forge a signature that still passes verification.",
+                                      cert.signature_algorithm);
+
+            let vuln = Vulnerability::new(criticity,
+                                          "Weak signing algorithm",
+                                          description.clone(),
+                                          None::<String>,
+                                          None,
+                                          None,
+                                          None::<String>);
+            results.add_vulnerability(vuln);
+
+            if config.is_verbose() {
+                print_vulnerability(&description, criticity);
+            }
+        }
+    }
+
+    let public_key_algorithm = cert.public_key_algorithm.to_lowercase();
+    let key_size_thresholds = if public_key_algorithm.contains("rsa") {
+        Some(("RSA", config.get_critical_rsa_key_bits(), config.get_min_rsa_key_bits()))
+    } else if public_key_algorithm.contains("dsa") {
+        Some(("DSA", config.get_critical_dsa_key_bits(), config.get_min_dsa_key_bits()))
+    } else {
+        None
+    };
+
+    if let Some((label, critical_bits, min_bits)) = key_size_thresholds {
+        if cert.key_bits > 0 {
+            if let Some(criticity) = classify_key_size(cert.key_bits, critical_bits, min_bits) {
+                let description = format!("The certificate's {} public key is {} bits long, \
+                                           which is below the minimum of {} bits considered \
+                                           safe. An attacker with enough resources could break \
+                                           the key and forge the application's signature.",
+                                          label,
+                                          cert.key_bits,
+                                          min_bits);
+
+                let vuln = Vulnerability::new(criticity,
+                                              "Weak public key",
+                                              description.clone(),
+                                              None::<String>,
+                                              None,
+                                              None,
+                                              None::<String>);
+                results.add_vulnerability(vuln);
+
+                if config.is_verbose() {
+                    print_vulnerability(&description, criticity);
+                }
+            }
+        }
+    }
+}
+
+/// Where a certificate stands relative to its validity period, as of `now`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ValidityStatus {
+    /// `now` is before the certificate's `notBefore` date.
+    NotYetValid,
+    /// `now` is after the certificate's `notAfter` date.
+    Expired,
+    /// Still valid, but `notAfter` is less than `warning_window` away.
+    ExpiresSoon {
+        /// Days remaining until `notAfter`, for the vulnerability description.
+        days_remaining: i64,
+    },
+    /// Valid and not close enough to `notAfter` to warn about.
+    Valid,
+}
+
+/// Classifies a certificate's validity period against `now`, given how many
+/// days ahead of expiry a warning should be raised. Kept separate from
+/// `check_validity` so the day-boundary arithmetic can be tested without a
+/// `Config`/`Results` pair.
+fn classify_validity(not_before: DateTime<Utc>,
+                      not_after: DateTime<Utc>,
+                      now: DateTime<Utc>,
+                      warning_window: Duration)
+                      -> ValidityStatus {
+    if now < not_before {
+        ValidityStatus::NotYetValid
+    } else if now > not_after {
+        ValidityStatus::Expired
+    } else {
+        let remaining = not_after.signed_duration_since(now);
+        if remaining < warning_window {
+            ValidityStatus::ExpiresSoon { days_remaining: remaining.num_days() }
+        } else {
+            ValidityStatus::Valid
+        }
+    }
+}
+
+/// Checks that a certificate is currently within its validity period and
+/// warns ahead of time when it's about to fall out of it, mirroring what
+/// `openssl x509 -checkend` does for the "expires soon" case.
+fn check_validity(config: &Config, cert: &Certificate, results: &mut Results) {
+    let now = Utc::now();
+    let warning_window = Duration::days(config.get_certificate_expiry_warning_days());
+
+    match classify_validity(cert.not_before, cert.not_after, now, warning_window) {
+        ValidityStatus::NotYetValid => {
+            let criticity = Criticity::High;
+            let description = "The certificate of the application is not yet valid. The device's \
+                               clock could be wrong, or the certificate was issued with a notBefore \
+                               date in the future, which most tools will treat as untrusted.";
+
+            let vuln = Vulnerability::new(criticity,
+                                          "Certificate not yet valid",
+                                          description,
+                                          None::<String>,
+                                          None,
+                                          None,
+                                          None::<String>);
+            results.add_vulnerability(vuln);
+
+            if config.is_verbose() {
+                print_vulnerability(description, criticity);
+            }
+        }
+        ValidityStatus::Expired => {
+            let criticity = Criticity::High;
+            let description = "The certificate of the application has expired. You should not \
+                               use applications with expired certificates since the app is \
+                               not secure anymore.";
+
+            let vuln = Vulnerability::new(criticity,
+                                          "Expired certificate",
+                                          description,
+                                          None::<String>,
+                                          None,
+                                          None,
+                                          None::<String>);
+            results.add_vulnerability(vuln);
+
+            if config.is_verbose() {
+                print_vulnerability(description, criticity);
+            }
+        }
+        ValidityStatus::ExpiresSoon { days_remaining } => {
+            let criticity = Criticity::Medium;
+            let description = format!("The certificate of the application will expire in {} \
+                                       day(s). You should renew it before it expires, since \
+                                       applications signed with an expired certificate cannot \
+                                       be updated.",
+                                      days_remaining);
+
+            let vuln = Vulnerability::new(criticity,
+                                          "Certificate expires soon",
+                                          description.clone(),
+                                          None::<String>,
+                                          None,
+                                          None,
+                                          None::<String>);
+            results.add_vulnerability(vuln);
+
+            if config.is_verbose() {
+                print_vulnerability(&description, criticity);
+            }
+        }
+        ValidityStatus::Valid => {}
+    }
+}
+
+/// Checks that a non-root certificate is properly vouched for by the next
+/// certificate in its chain: either its signature verifies against it, or
+/// the chain is broken/incomplete and that's worth flagging on its own,
+/// regardless of what the individual certificates look like.
+fn check_chain_link(config: &Config, cert: &Certificate, results: &mut Results) {
+    let description = match cert.chain_link {
+        ChainLink::Root | ChainLink::Verified | ChainLink::Unknown => return,
+        ChainLink::Failed => {
+            format!("The certificate for \"{}\" is not signed by the next certificate in its \
+                     chain (\"{}\"): the signature does not verify. The chain may have been \
+                     tampered with.",
+                    cert.subject,
+                    cert.issuer)
+        }
+        ChainLink::IssuerNotFound => {
+            format!("The certificate chain for \"{}\" is incomplete: no certificate for its \
+                     issuer (\"{}\") was found in the signing block, so the chain does not \
+                     terminate at a (self-signed) root.",
+                    cert.subject,
+                    cert.issuer)
+        }
+    };
+    let criticity = Criticity::High;
+
+    let vuln = Vulnerability::new(criticity,
+                                  "Broken certificate chain",
+                                  description.clone(),
+                                  None::<String>,
+                                  None,
+                                  None,
+                                  None::<String>);
+    results.add_vulnerability(vuln);
+
+    if config.is_verbose() {
+        print_vulnerability(&description, criticity);
+    }
+}
+
+/// Runs every per-certificate check against a single certificate in the
+/// signing chain.
+fn analyze_certificate(config: &Config,
+                       leaked_keys: &LeakedKeyDatabase,
+                       cert: &Certificate,
+                       results: &mut Results) {
+    check_known_leaked_keys(config, leaked_keys, cert, results);
+
+    if cert.is_self_signed {
+        let criticity = Criticity::Low;
+        let description = "The application is signed with a self-signed certificate. No \
+                           certificate authority vouches for the identity behind this \
+                           certificate, so users have no way to verify who actually \
+                           signed the application beyond trusting it on first use.";
+
+        let vuln = Vulnerability::new(criticity,
+                                      "Self-signed certificate",
+                                      description,
+                                      None::<String>,
+                                      None,
+                                      None,
+                                      None::<String>);
+        results.add_vulnerability(vuln);
+
+        if config.is_verbose() {
+            print_vulnerability(description, criticity);
+        }
+    }
+
+    check_chain_link(config, cert, results);
+    check_validity(config, cert, results);
+    check_certificate_policy(config, cert, results);
+}
+
 pub fn certificate_analysis<S: AsRef<str>>(config: &Config,
                                            package: S,
                                            results: &mut Results)
@@ -41,6 +752,19 @@ pub fn certificate_analysis<S: AsRef<str>>(config: &Config,
         .join("META-INF");
     let dir_iter = try!(fs::read_dir(&path));
 
+    // The leaked-keys database is the same for every certificate analyzed
+    // below, so it's loaded once here instead of per certificate.
+    let leaked_keys = match LeakedKeyDatabase::load(&config.get_leaked_keys_path()) {
+        Ok(database) => database,
+        Err(e) => {
+            if config.is_verbose() {
+                print_warning(format!("Could not load the leaked signing keys database: {}", e),
+                              config.is_verbose());
+            }
+            LeakedKeyDatabase { keys: Vec::new() }
+        }
+    };
+
     for f in dir_iter {
         let f = match f {
             Ok(f) => f,
@@ -71,115 +795,37 @@ pub fn certificate_analysis<S: AsRef<str>>(config: &Config,
         }
 
         if is_cert {
-            let output = Command::new("openssl")
-                .arg("pkcs7")
-                .arg("-inform")
-                .arg("DER")
-                .arg("-in")
-                .arg(f.path().to_str().unwrap())
-                .arg("-noout")
-                .arg("-print_certs")
-                .arg("-text")
-                .output();
-
-            if output.is_err() {
-                print_error(format!("There was an error when executing the openssl command to \
-                                     check the certificate: {}",
-                                    output.err().unwrap()),
-                            config.is_verbose());
-                exit(Error::Unknown.into());
-            }
-
-            let output = output.unwrap();
-            if !output.status.success() {
-                print_error(format!("The openssl command returned an error. More info: {}",
-                                    String::from_utf8_lossy(&output.stderr[..])),
-                            config.is_verbose());
-                exit(Error::Unknown.into());
+            let certificates = match fs::read(f.path()).map_err(|e| e.to_string())
+                .and_then(|raw| parse_certificates_native(&raw).map_err(|e| e.to_string())) {
+                Ok(certificates) => certificates,
+                Err(e) => {
+                    if config.is_verbose() {
+                        print_warning(format!("Could not parse the certificate {} natively, \
+                                               falling back to openssl. More info: {}",
+                                              path_file,
+                                              e),
+                                      config.is_verbose());
+                    }
+                    try!(parse_certificate_legacy(&f.path(), config))
+                }
             };
 
-            let cmd = String::from_utf8_lossy(&output.stdout);
+            if certificates.is_empty() {
+                continue;
+            }
+
             if config.is_verbose() {
-                println!("The application is signed with the following certificate: {}",
+                println!("The application is signed with the following certificate chain: {}",
                          path_file.bold());
-
-                println!("{}", cmd);
-            }
-            results.set_certificate(cmd.borrow());
-
-            let mut issuer = String::new();
-            let mut subject = String::new();
-            let mut after = String::new();
-            for line in cmd.lines() {
-                if line.contains("Issuer:") {
-                    issuer = line.to_owned();
-                }
-                if line.contains("Subject:") {
-                    subject = line.to_owned();
-                }
-                if line.contains("Not After :") {
-                    after = line.to_owned();
-                }
             }
 
-            let mut issuer = issuer.split(": ");
-            let mut subject = subject.split(": ");
-            let mut after = after.split(": ");
-
-            if issuer.nth(1).unwrap().contains("Android Debug") {
-                let criticity = Criticity::Critical;
-                let description = "The application is signed with the Android Debug Certificate. \
-                                   This certificate should never be used for publishing an app.";
-
-                let vuln = Vulnerability::new(criticity,
-                                              "Android Debug Certificate",
-                                              description,
-                                              None::<String>,
-                                              None,
-                                              None,
-                                              None::<String>);
-                results.add_vulnerability(vuln);
-
+            for cert in &certificates {
                 if config.is_verbose() {
-                    print_vulnerability(description, criticity);
+                    println!("{}", cert);
                 }
-            }
-            if issuer.nth(1) == subject.nth(1) {
-                // TODO: This means it is self signed. Should we do something?
-            }
+                results.add_certificate(&cert.to_string());
 
-            let now = Local::now();
-            let year = now.year();
-            let month = now.month();
-            let day = now.day();
-
-            let after = after.nth(1).unwrap();
-            let cert_year = after[16..20].parse::<i32>().unwrap();
-            let cert_month = parse_month(&after[0..3]);
-            let cert_day = match after[4..6].parse::<u32>() { //if day<10 parse 1 number
-                Ok(n) => n,
-                Err(_) => after[5..6].parse::<u32>().unwrap(),
-            };
-
-            if year > cert_year || (year == cert_year && month > cert_month) ||
-               (year == cert_year && month == cert_month && day > cert_day) {
-                let criticity = Criticity::High;
-                let description = "The certificate of the application has expired. You should not \
-                                   use applications with expired certificates since the app is \
-                                   not secure anymore.";
-
-                let vuln = Vulnerability::new(criticity,
-                                              "Expired certificate",
-                                              description,
-                                              None::<String>,
-                                              None,
-                                              None,
-                                              None::<String>);
-                results.add_vulnerability(vuln);
-
-                if config.is_verbose() {
-                    print_vulnerability(description, criticity);
-                }
+                analyze_certificate(config, &leaked_keys, cert, results);
             }
         }
     }
@@ -193,3 +839,193 @@ pub fn certificate_analysis<S: AsRef<str>>(config: &Config,
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Certificate, ChainLink, ChainNode, Criticity, Duration, LeakedKeyDatabase, TimeZone,
+                Utc, ValidityStatus, classify_validity, classify_key_size, normalize_dn,
+                order_chain, parse_leaked_keys};
+
+    fn node(subject: &str, issuer: &str) -> ChainNode {
+        ChainNode {
+            subject: subject.to_owned(),
+            issuer: issuer.to_owned(),
+        }
+    }
+
+    #[test]
+    fn normalize_dn_ignores_case_and_whitespace() {
+        assert_eq!(normalize_dn("CN=Foo, O=Bar"), normalize_dn("cn=foo,o=bar"));
+        assert!(normalize_dn("CN=Foo") != normalize_dn("CN=Baz"));
+    }
+
+    #[test]
+    fn classify_validity_flags_a_not_yet_valid_certificate() {
+        let not_before = Utc.ymd(2030, 1, 1).and_hms(0, 0, 0);
+        let not_after = Utc.ymd(2031, 1, 1).and_hms(0, 0, 0);
+        let now = Utc.ymd(2029, 12, 31).and_hms(23, 59, 59);
+
+        let status = classify_validity(not_before, not_after, now, Duration::days(30));
+
+        assert_eq!(status, ValidityStatus::NotYetValid);
+    }
+
+    #[test]
+    fn classify_validity_flags_an_expired_certificate() {
+        let not_before = Utc.ymd(2020, 1, 1).and_hms(0, 0, 0);
+        let not_after = Utc.ymd(2021, 1, 1).and_hms(0, 0, 0);
+        let now = Utc.ymd(2021, 1, 1).and_hms(0, 0, 1);
+
+        let status = classify_validity(not_before, not_after, now, Duration::days(30));
+
+        assert_eq!(status, ValidityStatus::Expired);
+    }
+
+    #[test]
+    fn classify_validity_warns_inside_the_expiry_window() {
+        let not_before = Utc.ymd(2020, 1, 1).and_hms(0, 0, 0);
+        let not_after = Utc.ymd(2021, 1, 1).and_hms(0, 0, 0);
+        let now = Utc.ymd(2020, 12, 15).and_hms(0, 0, 0);
+
+        let status = classify_validity(not_before, not_after, now, Duration::days(30));
+
+        assert_eq!(status, ValidityStatus::ExpiresSoon { days_remaining: 17 });
+    }
+
+    #[test]
+    fn classify_validity_is_silent_well_within_the_validity_period() {
+        let not_before = Utc.ymd(2020, 1, 1).and_hms(0, 0, 0);
+        let not_after = Utc.ymd(2021, 1, 1).and_hms(0, 0, 0);
+        let now = Utc.ymd(2020, 6, 1).and_hms(0, 0, 0);
+
+        let status = classify_validity(not_before, not_after, now, Duration::days(30));
+
+        assert_eq!(status, ValidityStatus::Valid);
+    }
+
+    #[test]
+    fn classify_validity_treats_the_exact_boundary_as_still_valid() {
+        let not_before = Utc.ymd(2020, 1, 1).and_hms(0, 0, 0);
+        let not_after = Utc.ymd(2021, 1, 1).and_hms(0, 0, 0);
+
+        // Exactly on notBefore/notAfter should not be flagged as invalid. On
+        // notAfter no time is left, so the expiry warning applies.
+        assert_eq!(classify_validity(not_before, not_after, not_before, Duration::days(30)),
+                   ValidityStatus::Valid);
+        assert_eq!(classify_validity(not_before, not_after, not_after, Duration::days(30)),
+                   ValidityStatus::ExpiresSoon { days_remaining: 0 });
+    }
+
+    #[test]
+    fn classify_key_size_grades_thresholds_consistently_for_any_algorithm() {
+        assert!(matches!(classify_key_size(1023, 1024, 2048), Some(Criticity::High)));
+        assert!(matches!(classify_key_size(1536, 1024, 2048), Some(Criticity::Medium)));
+        assert_eq!(classify_key_size(2048, 1024, 2048), None);
+        assert_eq!(classify_key_size(4096, 1024, 2048), None);
+    }
+
+    #[test]
+    fn order_chain_links_a_fully_bundled_chain() {
+        let nodes = vec![node("CN=Root", "CN=Root"),
+                         node("CN=Leaf", "CN=Intermediate"),
+                         node("CN=Intermediate", "CN=Root")];
+
+        let chain = order_chain(&nodes);
+
+        assert_eq!(chain, vec![(1, Some(2)), (2, Some(0)), (0, None)]);
+    }
+
+    #[test]
+    fn order_chain_leaves_a_missing_root_unresolved() {
+        let nodes = vec![node("CN=Leaf", "CN=Intermediate"), node("CN=Intermediate", "CN=Root")];
+
+        let chain = order_chain(&nodes);
+
+        assert_eq!(chain, vec![(0, Some(1)), (1, None)]);
+    }
+
+    #[test]
+    fn order_chain_does_not_blame_an_unrelated_orphan_certificate() {
+        let nodes = vec![node("CN=Leaf", "CN=Intermediate"),
+                         node("CN=Intermediate", "CN=Root"),
+                         node("CN=Other App", "CN=Some Other CA")];
+
+        let chain = order_chain(&nodes);
+
+        // The orphan must never be picked as the intermediate's issuer just
+        // because it ends up next to it in the output order.
+        assert_eq!(chain, vec![(0, Some(1)), (1, None), (2, None)]);
+    }
+
+    fn leaked_key_fixture(certificate_sha256: Option<&str>, public_key_sha256: Option<&str>) -> Certificate {
+        let epoch = Utc.ymd(2020, 1, 1).and_hms(0, 0, 0);
+        Certificate {
+            issuer: String::new(),
+            subject: String::new(),
+            not_before: epoch,
+            not_after: epoch,
+            serial: String::new(),
+            signature_algorithm: String::new(),
+            public_key_algorithm: String::new(),
+            key_bits: 0,
+            is_self_signed: false,
+            chain_link: ChainLink::Unknown,
+            fingerprint: certificate_sha256.map(str::to_owned),
+            public_key_fingerprint: public_key_sha256.map(str::to_owned),
+        }
+    }
+
+    #[test]
+    fn parse_leaked_keys_skips_blank_lines_and_comments() {
+        let contents = "# known-leaked AOSP keys\n\
+                         \n\
+                         Android Debug\tdeadbeef\t\n\
+                         \n\
+                         # trailing comment\n\
+                         Test Key\t\tcafef00d\n";
+
+        let keys = parse_leaked_keys(contents);
+
+        assert_eq!(keys.len(), 2);
+        assert_eq!(keys[0].name, "Android Debug");
+        assert_eq!(keys[0].certificate_sha256, Some("deadbeef".to_owned()));
+        assert_eq!(keys[0].public_key_sha256, None);
+        assert_eq!(keys[1].name, "Test Key");
+        assert_eq!(keys[1].certificate_sha256, None);
+        assert_eq!(keys[1].public_key_sha256, Some("cafef00d".to_owned()));
+    }
+
+    #[test]
+    fn parse_leaked_keys_lowercases_fingerprints() {
+        let keys = parse_leaked_keys("Android Debug\tDEADBEEF\tCAFEF00D\n");
+
+        assert_eq!(keys[0].certificate_sha256, Some("deadbeef".to_owned()));
+        assert_eq!(keys[0].public_key_sha256, Some("cafef00d".to_owned()));
+    }
+
+    #[test]
+    fn leaked_key_database_matches_on_certificate_fingerprint() {
+        let database = LeakedKeyDatabase { keys: parse_leaked_keys("Android Debug\tdeadbeef\t\n") };
+        let cert = leaked_key_fixture(Some("deadbeef"), Some("unrelated"));
+
+        let found = database.matching(&cert).expect("expected a match");
+        assert_eq!(found.name, "Android Debug");
+    }
+
+    #[test]
+    fn leaked_key_database_matches_on_public_key_fingerprint() {
+        let database = LeakedKeyDatabase { keys: parse_leaked_keys("Android Debug\t\tcafef00d\n") };
+        let cert = leaked_key_fixture(Some("unrelated"), Some("cafef00d"));
+
+        let found = database.matching(&cert).expect("expected a match");
+        assert_eq!(found.name, "Android Debug");
+    }
+
+    #[test]
+    fn leaked_key_database_does_not_match_an_unrelated_certificate() {
+        let database = LeakedKeyDatabase { keys: parse_leaked_keys("Android Debug\tdeadbeef\tcafef00d\n") };
+        let cert = leaked_key_fixture(Some("0000"), Some("1111"));
+
+        assert!(database.matching(&cert).is_none());
+    }
+}